@@ -1,25 +1,92 @@
-use super::Num;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
 use token::{Token, TokenStream, Symbol};
+use value::Value;
 
-/// The possible operators, each represents a function on two integers returning a new integer.
+/// The possible operators, each represents a function on two values returning a new value.
 /// This is not the same as Symbol token, because it does not include the parentheses
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Operator {
-    Summation, Subtraction, Multiplication,
-    LessThanComparison, BiggerThanComparison, EqualityComparison
+    Summation, Subtraction, Multiplication, Division, Modulo, Exponentiation,
+    LessThanComparison, BiggerThanComparison, EqualityComparison,
+    BitwiseAnd, BitwiseOr, BitwiseXor, ShiftLeft, ShiftRight,
 }
 
 impl Operator {
-    /// Applies the operator to two numbers and returns the result
-    pub fn apply(&self, left: Num, right: Num) -> Num {
+    /// Applies the operator to two values. If either operand is a `Float`, both are widened to
+    /// `f64` and the operation is carried out in floating point; otherwise it stays exact in i128,
+    /// except that `^` with a negative exponent is computed in floating point and yields a `Float`.
+    pub fn apply(&self, left: Value, right: Value) -> Result<Value, EvalError> {
+        match (left, right) {
+            (Value::Int(left), Value::Int(right)) => self.apply_int(left, right),
+            (left, right) => self.apply_float(left.as_f64(), right.as_f64()),
+        }
+    }
+
+    fn apply_int(&self, left: i128, right: i128) -> Result<Value, EvalError> {
+        use self::Operator::*;
+        match *self {
+            Summation => Ok(Value::Int(left + right)),
+            Subtraction => Ok(Value::Int(left - right)),
+            Multiplication => Ok(Value::Int(left * right)),
+            Division | Modulo if right == 0 => Err(EvalError::DivisionByZero),
+            Division => left.checked_div(right).map(Value::Int).ok_or(EvalError::Overflow),
+            Modulo => left.checked_rem(right).map(Value::Int).ok_or(EvalError::Overflow),
+            Exponentiation => {
+                if right < 0 {
+                    return self.apply_float(left as f64, right as f64);
+                }
+                let mut result: i128 = 1;
+                for _ in 0..right {
+                    result = result.checked_mul(left).ok_or(EvalError::Overflow)?;
+                }
+                Ok(Value::Int(result))
+            }
+            LessThanComparison => Ok(Value::Int(if left < right { 1 } else { 0 })),
+            BiggerThanComparison => Ok(Value::Int(if left > right { 1 } else { 0 })),
+            EqualityComparison => Ok(Value::Int(if left == right { 1 } else { 0 })),
+            BitwiseAnd => Ok(Value::Int(left & right)),
+            BitwiseOr => Ok(Value::Int(left | right)),
+            BitwiseXor => Ok(Value::Int(left ^ right)),
+            ShiftLeft => {
+                let amount = shift_amount(right)?;
+                left.checked_shl(amount).map(Value::Int).ok_or(EvalError::ShiftAmountOutOfRange)
+            }
+            ShiftRight => {
+                let amount = shift_amount(right)?;
+                left.checked_shr(amount).map(Value::Int).ok_or(EvalError::ShiftAmountOutOfRange)
+            }
+        }
+    }
+
+    fn apply_float(&self, left: f64, right: f64) -> Result<Value, EvalError> {
         use self::Operator::*;
         match *self {
-            Summation => left + right,
-            Subtraction => left - right,
-            Multiplication => left * right,
-            LessThanComparison => if left < right { 1 } else { 0 },
-            BiggerThanComparison => if left > right { 1 } else { 0 },
-            EqualityComparison => if left == right { 1 } else { 0 },
+            Summation => Ok(Value::Float(left + right)),
+            Subtraction => Ok(Value::Float(left - right)),
+            Multiplication => Ok(Value::Float(left * right)),
+            Division => {
+                if right == 0.0 {
+                    Err(EvalError::DivisionByZero)
+                } else {
+                    Ok(Value::Float(left / right))
+                }
+            }
+            Modulo => {
+                if right == 0.0 {
+                    Err(EvalError::DivisionByZero)
+                } else {
+                    Ok(Value::Float(left % right))
+                }
+            }
+            Exponentiation => Ok(Value::Float(left.powf(right))),
+            LessThanComparison => Ok(Value::Int(if left < right { 1 } else { 0 })),
+            BiggerThanComparison => Ok(Value::Int(if left > right { 1 } else { 0 })),
+            EqualityComparison => Ok(Value::Int(if left == right { 1 } else { 0 })),
+            BitwiseAnd | BitwiseOr | BitwiseXor | ShiftLeft | ShiftRight =>
+                Err(EvalError::NonIntegerBitwiseOperand),
         }
     }
 
@@ -31,194 +98,370 @@ impl Operator {
             Plus => Some(Summation),
             Minus => Some(Subtraction),
             Asterisk => Some(Multiplication),
+            Slash => Some(Division),
+            Percent => Some(Modulo),
+            Caret => Some(Exponentiation),
             LessThan => Some(LessThanComparison),
             BiggerThan => Some(BiggerThanComparison),
             Equal => Some(EqualityComparison),
+            Amper => Some(BitwiseAnd),
+            Pipe => Some(BitwiseOr),
+            CaretCaret => Some(BitwiseXor),
+            LeftShift => Some(ShiftLeft),
+            RightShift => Some(ShiftRight),
             _ => None,
         }
     }
+
+    /// Returns the (left, right) binding powers used by the precedence-climbing parser.
+    /// A left-associative operator has left < right, a right-associative one has left > right;
+    /// a higher pair of powers binds tighter than a lower one. The bitwise operators sit above
+    /// the comparisons (bitwise-or loosest, then xor, then and), and shifts bind just tighter
+    /// than the additive operators.
+    fn binding_power(&self) -> (u8, u8) {
+        use self::Operator::*;
+        match *self {
+            LessThanComparison | BiggerThanComparison | EqualityComparison => (1, 2),
+            BitwiseOr => (3, 4),
+            BitwiseXor => (5, 6),
+            BitwiseAnd => (7, 8),
+            Summation | Subtraction => (9, 10),
+            ShiftLeft | ShiftRight => (11, 12),
+            Multiplication | Division | Modulo => (13, 14),
+            Exponentiation => (16, 15),
+        }
+    }
 }
 
 /// An AST node representing an expression
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Debug)]
 pub enum Expression {
     /// A constant literal
-    Const(Num),
+    Const(Value),
     /// Operation on two subexpressions
     Action {
         left: Box<Expression>,
         action: Operator,
         right: Box<Expression>,
     },
+    /// Unary negation of a subexpression
+    Negation(Box<Expression>),
+    /// A reference to a bound variable (this also covers `Ans`, which is just pre-bound)
+    Var(String),
+    /// `let name = value; body`, binding `name` to `value`'s result for the scope of `body`
+    Let {
+        name: String,
+        value: Box<Expression>,
+        body: Box<Expression>,
+    },
 }
 
 impl Expression {
-    /// Evaluates the expression and returns its value
-    pub fn evaluate(&self) -> Num {
+    /// Evaluates the expression and returns its value, looking up and binding variables in `env`
+    pub fn evaluate(&self, env: &mut HashMap<String, Value>) -> Result<Value, EvalError> {
         use self::Expression::*;
         match *self {
-            Const(val) => val,
+            Const(val) => Ok(val),
             Action { ref left, action, ref right } =>
-                action.apply(left.evaluate(), right.evaluate()),
+                action.apply(left.evaluate(env)?, right.evaluate(env)?),
+            Negation(ref expr) => Ok(-expr.evaluate(env)?),
+            Var(ref name) => env.get(name).cloned()
+                .ok_or_else(|| EvalError::UndefinedVariable(name.clone())),
+            Let { ref name, ref value, ref body } => {
+                let value = value.evaluate(env)?;
+                let shadowed = env.insert(name.clone(), value);
+                let result = body.evaluate(env);
+                match shadowed {
+                    Some(prev) => { env.insert(name.clone(), prev); }
+                    None => { env.remove(name); }
+                }
+                result
+            }
+        }
+    }
+}
+
+/// Errors produced while evaluating an `Expression`
+#[derive(Clone, PartialEq, Debug)]
+pub enum EvalError {
+    /// A `/` or `%` operation with a right-hand side of zero
+    DivisionByZero,
+    /// A `^`, `/` or `%` operation on integers that overflowed i128
+    Overflow,
+    /// A variable (or `Ans`) was referenced before it was bound
+    UndefinedVariable(String),
+    /// A bitwise or shift operator was applied to a `Float` operand
+    NonIntegerBitwiseOperand,
+    /// A shift amount that was negative or at least as wide as the operand itself
+    ShiftAmountOutOfRange,
+}
+
+/// Validates a shift amount for `<<`/`>>`, which `i128::checked_shl`/`checked_shr` take as `u32`
+fn shift_amount(amount: i128) -> Result<u32, EvalError> {
+    if amount < 0 || amount > u32::MAX as i128 {
+        Err(EvalError::ShiftAmountOutOfRange)
+    } else {
+        Ok(amount as u32)
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EvalError::DivisionByZero => write!(f, "division by zero"),
+            EvalError::Overflow => write!(f, "integer overflow"),
+            EvalError::UndefinedVariable(ref name) => write!(f, "undefined variable `{}`", name),
+            EvalError::NonIntegerBitwiseOperand =>
+                write!(f, "bitwise operators require integer operands"),
+            EvalError::ShiftAmountOutOfRange => write!(f, "shift amount out of range"),
         }
     }
 }
 
+impl Error for EvalError {}
+
+/// Errors produced while building an `Expression` out of a token list
+#[derive(Clone, PartialEq, Debug)]
+pub enum ParseError {
+    /// A token was encountered where it doesn't belong
+    UnexpectedToken { found: Token },
+    /// The token stream ended before a complete expression was read
+    UnexpectedEof,
+    /// An opening parenthesis was never matched by a closing one
+    ExpectedRightParen,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::UnexpectedToken { ref found } => write!(f, "unexpected token {:?}", found),
+            ParseError::UnexpectedEof => write!(f, "unexpected end of input"),
+            ParseError::ExpectedRightParen => write!(f, "expected a closing parenthesis"),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
 /// Tries to read a primary from the token stream.
 /// A primary is either a constant literal or a subexpression wrapped in parentheses.
-///
-/// # Panics
-/// When the token stream is malformed and the parsed failed to extract a primary
-fn read_primary(stream: &mut TokenStream) -> Expression {
-    let token = stream.read().unwrap();
+fn read_primary(stream: &mut TokenStream) -> Result<Expression, ParseError> {
+    let token = stream.read().ok_or(ParseError::UnexpectedEof)?;
     match token {
+        Token::Op(Symbol::Minus) => {
+            stream.advance();
+            let expr = read_primary(stream)?;
+            Ok(Expression::Negation(Box::new(expr)))
+        }
         Token::Number(num) => {
             stream.advance();
-            Expression::Const(num)
+            Ok(Expression::Const(num))
         },
-        Token::Op(Symbol::LeftParenthesis) => {
+        Token::Ident(name) => {
             stream.advance();
-            let expr = read_relation(stream);
-            assert_eq!(stream.read().unwrap(), Token::Op(Symbol::RightParenthesis));
+            Ok(Expression::Var(name))
+        },
+        Token::Op(Symbol::LeftParenthesis) => {
             stream.advance();
-            expr
+            let expr = parse_expr(stream, 0)?;
+            match stream.read() {
+                Some(Token::Op(Symbol::RightParenthesis)) => {
+                    stream.advance();
+                    Ok(expr)
+                }
+                Some(_) => Err(ParseError::ExpectedRightParen),
+                None => Err(ParseError::UnexpectedEof),
+            }
         }
-        token => panic!("Unexpected token {:?}", token),
+        found => Err(ParseError::UnexpectedToken { found }),
     }
 }
 
-/// Tries to read a relation from the token stream.
-/// A relation is two subexpressions compared with each other by one of three comparison operators.
-///
-/// # Panics
-/// When the token stream is malformed and the parsed failed to extract a relation
-fn read_relation(tokens: &mut TokenStream) -> Expression {
-    let mut expr = read_term(tokens);
-    fn is_relation_symbol(s: &Token) -> bool {
-        if let Token::Op(s) = *s {
-            s == Symbol::LessThan || s == Symbol::BiggerThan || s == Symbol::Equal
-        } else { false }
-    }
-    while let Some(Token::Op(s)) = tokens.read().filter(is_relation_symbol) {
-        tokens.advance();
-        expr = Expression::Action {
-            left: Box::new(expr),
-            action: Operator::from_symbol(s).unwrap(),
-            right: Box::new(read_term(tokens)),
-        };
+/// Reads a `let name = value; body` expression from the token stream, assuming the cursor is on
+/// the `let` keyword
+fn read_let(stream: &mut TokenStream) -> Result<Expression, ParseError> {
+    stream.advance();
+    let name = match stream.read() {
+        Some(Token::Ident(name)) => { stream.advance(); name }
+        Some(found) => return Err(ParseError::UnexpectedToken { found }),
+        None => return Err(ParseError::UnexpectedEof),
+    };
+    match stream.read() {
+        Some(Token::Op(Symbol::Equal)) => stream.advance(),
+        Some(found) => return Err(ParseError::UnexpectedToken { found }),
+        None => return Err(ParseError::UnexpectedEof),
     }
-    expr
-}
-
-/// Tries to read a term from the token stream.
-/// A term is a sum or a difference of two subexpressions.
-///
-/// # Panics
-/// When the token stream is malformed and the parsed failed to extract a term
-fn read_term(tokens: &mut TokenStream) -> Expression {
-    let mut expr = read_factor(tokens);
-    fn is_term_symbol(s: &Token) -> bool {
-        if let Token::Op(s) = *s {
-            s == Symbol::Plus || s == Symbol::Minus
-        } else { false }
-    }
-    while let Some(Token::Op(s)) = tokens.read().filter(is_term_symbol) {
-        tokens.advance();
-        expr = Expression::Action {
-            left: Box::new(expr),
-            action: Operator::from_symbol(s).unwrap(),
-            right: Box::new(read_factor(tokens)),
-        };
+    let value = parse_expr(stream, 0)?;
+    match stream.read() {
+        Some(Token::Op(Symbol::Semicolon)) => stream.advance(),
+        Some(found) => return Err(ParseError::UnexpectedToken { found }),
+        None => return Err(ParseError::UnexpectedEof),
     }
-    expr
+    let body = parse_expr(stream, 0)?;
+    Ok(Expression::Let { name, value: Box::new(value), body: Box::new(body) })
 }
 
-/// Tries to read a factor from the token stream.
-/// A factor is a multiplication of two subexpressions.
-///
-/// # Panics
-/// When the token stream is malformed and the parsed failed to extract a factor
-fn read_factor(tokens: &mut TokenStream) -> Expression {
-    let mut expr = read_primary(tokens);
-    fn is_factor_symbol(s: &Token) -> bool {
-        if let Token::Op(s) = *s {
-            s == Symbol::Asterisk
-        } else { false }
-    }
-    while let Some(Token::Op(s)) = tokens.read().filter(is_factor_symbol) {
-        tokens.advance();
-        expr = Expression::Action {
-            left: Box::new(expr),
-            action: Operator::from_symbol(s).unwrap(),
-            right: Box::new(read_primary(tokens)),
+/// Reads an expression from the token stream using precedence climbing: a primary is read as the
+/// left operand, then for as long as the next token is an infix operator whose left binding power
+/// is at least `min_bp`, it is consumed and the right operand is parsed recursively at its right
+/// binding power. Replaces the old ladder of `read_relation`/`read_term`/`read_factor` with a
+/// single function driven by `Operator::binding_power`, so adding an operator is one table row.
+fn parse_expr(stream: &mut TokenStream, min_bp: u8) -> Result<Expression, ParseError> {
+    if let Some(Token::Let) = stream.read() {
+        return read_let(stream);
+    }
+    let mut left = read_primary(stream)?;
+    loop {
+        let op = match stream.read() {
+            Some(Token::Op(s)) => Operator::from_symbol(s),
+            _ => None,
+        };
+        let op = match op {
+            Some(op) => op,
+            None => break,
+        };
+        let (left_bp, right_bp) = op.binding_power();
+        if left_bp < min_bp {
+            break;
+        }
+        stream.advance();
+        let right = parse_expr(stream, right_bp)?;
+        left = Expression::Action {
+            left: Box::new(left),
+            action: op,
+            right: Box::new(right),
         };
     }
-    expr
+    Ok(left)
 }
 
 /// Parses the input vector of tokens into an `Expression`
-///
-/// # Panics
-/// When the token list is malformed
-pub fn parse_tokens(tokens: Vec<Token>) -> Expression {
+pub fn parse_tokens(tokens: Vec<Token>) -> Result<Expression, ParseError> {
     let mut stream = TokenStream::new(tokens);
-    let expr = read_relation(&mut stream);
-    assert_eq!(stream.read(), None);
-    expr
+    let expr = parse_expr(&mut stream, 0)?;
+    match stream.read() {
+        None => Ok(expr),
+        Some(found) => Err(ParseError::UnexpectedToken { found }),
+    }
 }
 
 /// Unit tests for the AST stage
 #[cfg(test)]
 mod tests{
+    use std::collections::HashMap;
     use super::Token::*;
     use super::Symbol::*;
     use super::parse_tokens;
+    use value::Value;
+    use value::Value::{Int, Float};
+
+    fn eval(expr: &super::Expression) -> Value {
+        expr.evaluate(&mut HashMap::new()).unwrap()
+    }
 
     #[test]
     fn test_simple_1() {
-        let input = vec![Number(2), Op(Plus), Number(2)];
-        let expr = parse_tokens(input);
-        assert_eq!(expr.evaluate(), 4);
+        let input = vec![Number(Int(2)), Op(Plus), Number(Int(2))];
+        let expr = parse_tokens(input).unwrap();
+        assert_eq!(eval(&expr), Int(4));
     }
 
     #[test]
     fn test_simple_2() {
-        let input = vec![Number(2), Op(Plus), Number(2), Op(Asterisk), Number(2)];
-        let expr = parse_tokens(input);
-        assert_eq!(expr.evaluate(), 6);
+        let input = vec![Number(Int(2)), Op(Plus), Number(Int(2)), Op(Asterisk), Number(Int(2))];
+        let expr = parse_tokens(input).unwrap();
+        assert_eq!(eval(&expr), Int(6));
     }
 
     #[test]
     fn test_simple_3() {
         let input =
-            vec![Op(LeftParenthesis), Number(2), Op(Plus), Number(4), Op(RightParenthesis),
-                 Op(Asterisk), Number(3)];
-        let expr = parse_tokens(input);
-        assert_eq!(expr.evaluate(), 18);
+            vec![Op(LeftParenthesis), Number(Int(2)), Op(Plus), Number(Int(4)), Op(RightParenthesis),
+                 Op(Asterisk), Number(Int(3))];
+        let expr = parse_tokens(input).unwrap();
+        assert_eq!(eval(&expr), Int(18));
+    }
+
+    #[test]
+    fn test_let() {
+        let input = vec![
+            Let, Ident("x".to_string()), Op(Equal), Number(Int(5)), Op(Plus), Number(Int(6)), Op(Semicolon),
+            Ident("x".to_string()), Op(Asterisk), Number(Int(2)),
+        ];
+        let expr = parse_tokens(input).unwrap();
+        assert_eq!(eval(&expr), Int(22));
+    }
+
+    #[test]
+    fn test_undefined_variable() {
+        let input = vec![Ident("x".to_string())];
+        let expr = parse_tokens(input).unwrap();
+        assert!(expr.evaluate(&mut HashMap::new()).is_err());
     }
 
     #[test]
-    #[should_panic]
     fn test_fail_1() {
         let input =
-            vec![Op(LeftParenthesis), Number(2), Op(Plus), Number(4), Op(LeftParenthesis),
-                 Op(Asterisk), Number(3)];
-        let _ = parse_tokens(input);
+            vec![Op(LeftParenthesis), Number(Int(2)), Op(Plus), Number(Int(4)), Op(LeftParenthesis),
+                 Op(Asterisk), Number(Int(3))];
+        assert!(parse_tokens(input).is_err());
     }
 
     #[test]
-    #[should_panic]
     fn test_fail_2() {
         let input =
-            vec![Number(2), Op(Plus), Number(4), Number(4), Op(Asterisk), Number(3)];
-        let _ = parse_tokens(input);
+            vec![Number(Int(2)), Op(Plus), Number(Int(4)), Number(Int(4)), Op(Asterisk), Number(Int(3))];
+        assert!(parse_tokens(input).is_err());
     }
 
     #[test]
-    #[should_panic]
     fn test_fail_3() {
         let input =
-            vec![Number(2), Op(Plus), Number(4), Number(4), Op(Asterisk), Op(Asterisk), Number(3)];
-        let _ = parse_tokens(input);
+            vec![Number(Int(2)), Op(Plus), Number(Int(4)), Number(Int(4)), Op(Asterisk), Op(Asterisk), Number(Int(3))];
+        assert!(parse_tokens(input).is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_float_promotion() {
+        let input = vec![Number(Int(1)), Op(Plus), Number(Float(0.5))];
+        let expr = parse_tokens(input).unwrap();
+        assert_eq!(eval(&expr), Float(1.5));
+    }
+
+    #[test]
+    fn test_float_comparison_stays_int() {
+        let input = vec![Number(Float(1.5)), Op(BiggerThan), Number(Int(1))];
+        let expr = parse_tokens(input).unwrap();
+        assert_eq!(eval(&expr), Int(1));
+    }
+
+    #[test]
+    fn test_bitwise_precedence() {
+        // `&` binds tighter than `|`, so this is (6 & 3) | 1 = 2 | 1 = 3
+        let input = vec![Number(Int(6)), Op(Amper), Number(Int(3)), Op(Pipe), Number(Int(1))];
+        let expr = parse_tokens(input).unwrap();
+        assert_eq!(eval(&expr), Int(3));
+    }
+
+    #[test]
+    fn test_bitwise_xor() {
+        let input = vec![Number(Int(6)), Op(CaretCaret), Number(Int(3))];
+        let expr = parse_tokens(input).unwrap();
+        assert_eq!(eval(&expr), Int(5));
+    }
+
+    #[test]
+    fn test_shift_binds_tighter_than_additive() {
+        // shifts bind just tighter than `+`/`-`, so this is 1 + (1 << 4) = 17
+        let input = vec![Number(Int(1)), Op(Plus), Number(Int(1)), Op(LeftShift), Number(Int(4))];
+        let expr = parse_tokens(input).unwrap();
+        assert_eq!(eval(&expr), Int(17));
+    }
+
+    #[test]
+    fn test_bitwise_on_float_is_error() {
+        let input = vec![Number(Float(1.0)), Op(Amper), Number(Int(1))];
+        let expr = parse_tokens(input).unwrap();
+        assert!(expr.evaluate(&mut HashMap::new()).is_err());
+    }
+}
@@ -1,51 +1,177 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
 use std::fs::{read_to_string, write};
+use std::io::{self, BufRead, Write as IoWrite};
 
 mod token;
 mod ast;
+mod value;
 
-type Num = i128;
+use value::Value;
 
-const INPUT: &'static str = "in.txt";
 const OUTPUT: &'static str = "out.txt";
 
-fn read_input() -> String {
-    read_to_string(INPUT).expect("something went wrong reading the file")
+/// Any stage of the pipeline can fail; this wraps all three so `Evaluator::execute` has a single
+/// error type
+#[derive(Clone, PartialEq, Debug)]
+enum ExecuteError {
+    Lex(token::LexError),
+    Parse(ast::ParseError),
+    Eval(ast::EvalError),
+}
+
+impl fmt::Display for ExecuteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExecuteError::Lex(ref e) => write!(f, "{}", e),
+            ExecuteError::Parse(ref e) => write!(f, "{}", e),
+            ExecuteError::Eval(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<token::LexError> for ExecuteError {
+    fn from(e: token::LexError) -> Self {
+        ExecuteError::Lex(e)
+    }
+}
+
+impl From<ast::ParseError> for ExecuteError {
+    fn from(e: ast::ParseError) -> Self {
+        ExecuteError::Parse(e)
+    }
+}
+
+impl From<ast::EvalError> for ExecuteError {
+    fn from(e: ast::EvalError) -> Self {
+        ExecuteError::Eval(e)
+    }
+}
+
+/// Runs expressions one at a time, binding each successful result to `Ans` for the ones after it;
+/// `let` bindings last only for their own body and are not kept between expressions
+struct Evaluator {
+    env: HashMap<String, Value>,
+}
+
+impl Evaluator {
+    fn new() -> Self {
+        Evaluator { env: HashMap::new() }
+    }
+
+    fn execute(&mut self, expr: &str) -> Result<Value, ExecuteError> {
+        let tokens = token::tokenize(expr)?;
+        let expr = ast::parse_tokens(tokens)?;
+        let result = expr.evaluate(&mut self.env)?;
+        self.env.insert("Ans".to_string(), result);
+        Ok(result)
+    }
 }
 
 fn write_output(str: &str) {
     write(OUTPUT, str).unwrap()
 }
 
-fn execute(expr: &str) -> Num {
-    ast::parse_tokens(token::tokenize(expr)).evaluate()
+/// Batch mode: evaluates every line of `path` and writes the results to `OUTPUT`
+fn run_file(path: &str) {
+    let test = read_to_string(path).expect("something went wrong reading the file");
+    let mut evaluator = Evaluator::new();
+    let mut output = String::new();
+    for (line_no, line) in test.lines().enumerate() {
+        match evaluator.execute(line) {
+            Ok(result) => output += &result.to_string(),
+            Err(err) => {
+                eprintln!("line {}: {}", line_no + 1, err);
+                output += "error";
+            }
+        }
+        output += "\n";
+    }
+    write_output(&output);
+}
+
+/// Interactive mode: reads expressions from stdin one at a time and prints their results
+fn run_repl() {
+    let mut evaluator = Evaluator::new();
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    loop {
+        print!("> ");
+        stdout.flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        match evaluator.execute(line.trim_end()) {
+            Ok(result) => println!("{}", result),
+            Err(err) => eprintln!("{}", err),
+        }
+    }
 }
 
 fn main() {
-    let test = read_input();
-    let output = test.lines()
-        .map(execute)
-        .fold(String::new(), |a, i| a + &i.to_string() + "\n");
-    write_output(&output);
+    match env::args().nth(1) {
+        Some(path) => run_file(&path),
+        None => run_repl(),
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{execute, Num};
-    const TESTS: &[(&'static str, Num)] = &[
-        ("1>0", 1),
-        ("1<0", 0),
-        ("1=1", 1),
-        ("1=0", 0),
-        ("1+2", 3),
-        ("1+2*3", 7),
-        ("(1+2)*3", 9),
+    use super::Evaluator;
+    use value::Value;
+    use value::Value::{Int, Float};
+
+    const TESTS: &[(&'static str, Value)] = &[
+        ("1>0", Int(1)),
+        ("1<0", Int(0)),
+        ("1=1", Int(1)),
+        ("1=0", Int(0)),
+        ("1+2", Int(3)),
+        ("1+2*3", Int(7)),
+        ("(1+2)*3", Int(9)),
+        ("7/2", Int(3)),
+        ("7%2", Int(1)),
+        ("2^3^2", Int(512)),
+        ("-3+5", Int(2)),
+        ("2*-5", Int(-10)),
+        ("1 + 2", Int(3)),
+        ("let x = 5 + 6; x * 2", Int(22)),
+        ("1+0.5", Float(1.5)),
+        ("3.5*2", Float(7.0)),
+        ("6&3|1", Int(3)),
+        ("6^^3", Int(5)),
+        ("1<<4", Int(16)),
+        ("1+1<<4", Int(17)),
     ];
 
     #[test]
     fn test() {
         for (input, output) in TESTS {
             println!("Evaluating {}", *input);
-            assert_eq!(*output, execute(*input));
+            assert_eq!(*output, Evaluator::new().execute(*input).unwrap());
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_errors_do_not_panic() {
+        let mut evaluator = Evaluator::new();
+        assert!(evaluator.execute("abc").is_err());
+        assert!(evaluator.execute("(1+2").is_err());
+        assert!(evaluator.execute("1/0").is_err());
+    }
+
+    #[test]
+    fn test_ans() {
+        let mut evaluator = Evaluator::new();
+        assert_eq!(evaluator.execute("2+2").unwrap(), Int(4));
+        assert_eq!(evaluator.execute("Ans*10").unwrap(), Int(40));
+    }
+
+    #[test]
+    fn test_ans_undefined() {
+        let mut evaluator = Evaluator::new();
+        assert!(evaluator.execute("Ans").is_err());
+    }
+}
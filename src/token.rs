@@ -1,13 +1,23 @@
-use super::Num;
+use std::error::Error;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use value::Value;
 
 /// Any non-numeric and non-whitespace characters,
 /// currently limited to operators and parentheses
 #[derive(PartialEq, Eq, Debug, Copy, Clone)]
 pub enum Symbol {
     Plus, Minus,
-    Asterisk,
+    Asterisk, Slash, Percent, Caret,
     LessThan, BiggerThan, Equal,
     LeftParenthesis, RightParenthesis,
+    Semicolon,
+    Amper, Pipe,
+    /// Bitwise XOR; `^` is already taken by exponentiation, so this language spells it `^^`
+    CaretCaret,
+    LeftShift, RightShift,
 }
 
 impl Symbol {
@@ -16,21 +26,31 @@ impl Symbol {
             '+' => Some(Symbol::Plus),
             '-' => Some(Symbol::Minus),
             '*' => Some(Symbol::Asterisk),
+            '/' => Some(Symbol::Slash),
+            '%' => Some(Symbol::Percent),
+            '^' => Some(Symbol::Caret),
             '>' => Some(Symbol::BiggerThan),
             '<' => Some(Symbol::LessThan),
             '=' => Some(Symbol::Equal),
             '(' => Some(Symbol::LeftParenthesis),
             ')' => Some(Symbol::RightParenthesis),
+            ';' => Some(Symbol::Semicolon),
+            '&' => Some(Symbol::Amper),
+            '|' => Some(Symbol::Pipe),
             _ => None,
         }
     }
 }
 
-#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum Token {
     Op(Symbol),
-    Number(Num),
+    Number(Value),
     Whitespace(char),
+    /// An identifier, e.g. a variable name or `Ans`
+    Ident(String),
+    /// The `let` keyword
+    Let,
 }
 
 /// Wrapper for the vector of tokens providing stream-like API
@@ -49,64 +69,141 @@ impl TokenStream {
     /// returns None if the stream has finished
     pub fn read(&mut self) -> Option<Token> {
         if self.pos < self.tokens.len() {
-            Some(self.tokens[self.pos])
+            Some(self.tokens[self.pos].clone())
         } else {
             None
         }
     }
 
-    /// Creates a new stream from a vector of tokens
+    /// Creates a new stream from a vector of tokens, discarding whitespace: it only exists so the
+    /// tokenizer stage can be tested on its own, and carries no meaning for the parser.
     pub fn new(tokens: Vec<Token>) -> Self {
+        let tokens = tokens.into_iter().filter(|t| !is_whitespace(t)).collect();
         TokenStream { tokens, pos: 0 }
     }
 }
 
+fn is_whitespace(token: &Token) -> bool {
+    matches!(*token, Token::Whitespace(_))
+}
+
+/// Errors produced while splitting the input into tokens
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum LexError {
+    /// A character that is neither whitespace, a digit, a letter nor a known symbol
+    IllegalChar { pos: u32 },
+    /// A number literal starting with a `0` followed by further digits
+    LeadingZero { pos: u32 },
+    /// A decimal point that isn't followed by at least one fractional digit
+    MalformedFloat { pos: u32 },
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LexError::IllegalChar { pos } =>
+                write!(f, "illegal character at position {}", pos),
+            LexError::LeadingZero { pos } =>
+                write!(f, "number with a leading zero at position {}", pos),
+            LexError::MalformedFloat { pos } =>
+                write!(f, "decimal point without fractional digits at position {}", pos),
+        }
+    }
+}
+
+impl Error for LexError {}
+
+/// Reads a run of decimal digits starting at the cursor and parses it as an integer. Used for both
+/// the integer and fractional part of a number literal; with `reject_leading_zero` set (the integer
+/// part) a `0` followed by further digits is an error, while the fractional part may start with zeros.
+fn read_int(chars: &mut Peekable<CharIndices>, reject_leading_zero: bool) -> Result<i128, LexError> {
+    let start = chars.peek().map(|&(pos, _)| pos).unwrap();
+    let mut value: i128 = 0;
+    while let Some(&(pos, c)) = chars.peek() {
+        match c.to_digit(10) {
+            Some(digit) => {
+                if reject_leading_zero && value == 0 && pos != start {
+                    return Err(LexError::LeadingZero { pos: pos as u32 });
+                }
+                value = value * 10 + digit as i128;
+                chars.next();
+            }
+            None => break,
+        }
+    }
+    Ok(value)
+}
 
 /// Transforms the input string into a vector of tokens
-///
-/// # Panics
-///
-/// Panics on encountering incorrect characters or number literals
-pub fn tokenize(input: &str) -> Vec<Token> {
-    let mut current_number: Option<Num> = None;
+pub fn tokenize(input: &str) -> Result<Vec<Token>, LexError> {
     let mut tokens = vec![];
-    let mut i: u32 = 0;
-
-    for c in input.chars() {
-        match (c, c.to_digit(10), Symbol::parse_char(c), current_number) {
-            // Handle whitespace chars
-            (c, _, _, Some(num)) if c.is_whitespace() => {
-                tokens.push(Token::Number(num));
-                current_number = None;
-                tokens.push(Token::Whitespace(c))
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(pos, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            tokens.push(Token::Whitespace(c));
+        } else if c.is_ascii_digit() {
+            let int_part = read_int(&mut chars, true)?;
+            if let Some(&(dot_pos, '.')) = chars.peek() {
+                chars.next();
+                let frac_start = match chars.peek() {
+                    Some(&(pos, _)) => pos,
+                    None => return Err(LexError::MalformedFloat { pos: dot_pos as u32 }),
+                };
+                let frac_part = read_int(&mut chars, false)?;
+                let frac_digits = chars.peek().map(|&(pos, _)| pos).unwrap_or(input.len()) - frac_start;
+                if frac_digits == 0 {
+                    return Err(LexError::MalformedFloat { pos: dot_pos as u32 });
+                }
+                let value = int_part as f64 + frac_part as f64 / 10f64.powi(frac_digits as i32);
+                tokens.push(Token::Number(Value::Float(value)));
+            } else {
+                tokens.push(Token::Number(Value::Int(int_part)));
+            }
+        } else if c.is_alphabetic() {
+            let mut ident = String::new();
+            while let Some(&(_, c)) = chars.peek() {
+                if c.is_alphabetic() {
+                    ident.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            match ident.as_str() {
+                "let" => tokens.push(Token::Let),
+                _ => tokens.push(Token::Ident(ident)),
             }
-            (c, _, _, None) if c.is_whitespace() =>
-                tokens.push(Token::Whitespace(c)),
-
-            // Handle digits
-            (_, Some(digit), _, Some(num)) if num != 0 =>
-                current_number = Some(num * 10 + digit as Num),
-            (_, Some(digit), _, None) =>
-                current_number = Some(digit as Num),
-
-            // Handle operators
-            (_, None, Some(op), Some(num)) => {
-                tokens.push(Token::Number(num));
-                current_number = None;
-                tokens.push(Token::Op(op))
-            },
-            (_, None, Some(op), None) =>
-                tokens.push(Token::Op(op)),
-
-            // TODO error handling
-            _ => panic!("Tokenization error at location {}", i)
+        } else if c == '<' || c == '>' {
+            chars.next();
+            let doubled = match chars.peek() {
+                Some(&(_, next_c)) if next_c == c => { chars.next(); true }
+                _ => false,
+            };
+            let op = match (c, doubled) {
+                ('<', true) => Symbol::LeftShift,
+                ('>', true) => Symbol::RightShift,
+                ('<', false) => Symbol::LessThan,
+                _ => Symbol::BiggerThan,
+            };
+            tokens.push(Token::Op(op));
+        } else if c == '^' {
+            chars.next();
+            let doubled = match chars.peek() {
+                Some(&(_, '^')) => { chars.next(); true }
+                _ => false,
+            };
+            let op = if doubled { Symbol::CaretCaret } else { Symbol::Caret };
+            tokens.push(Token::Op(op));
+        } else if let Some(op) = Symbol::parse_char(c) {
+            chars.next();
+            tokens.push(Token::Op(op));
+        } else {
+            return Err(LexError::IllegalChar { pos: pos as u32 });
         }
-        i += 1;
-    }
-    if let Some(num) = current_number {
-        tokens.push(Token::Number(num));
     }
-    tokens
+    Ok(tokens)
 }
 
 /// Unit tests for the tokenizer stage
@@ -115,40 +212,55 @@ mod tests {
     use super::*;
     use super::Token::*;
     use super::Symbol::*;
-    struct TestCase(&'static str, &'static [Token]);
-
-    const TESTS_POSITIVE: &[TestCase] = &[
-        TestCase("2+2",
-                 &[Number(2), Op(Plus), Number(2)]),
-        TestCase("2++2",
-                 &[Number(2), Op(Plus), Op(Plus), Number(2)]),
-        TestCase("",
-                 &[]),
-        TestCase("((2+555)+100)0",
-                 &[Op(LeftParenthesis), Op(LeftParenthesis), Number(2), Op(Plus), Number(555),
-                     Op(RightParenthesis), Op(Plus), Number(100), Op(RightParenthesis), Number(0)]),
-        TestCase("2 * 10",
-                 &[Number(2), Whitespace(' '), Op(Asterisk), Whitespace(' '), Number(10)]),
-    ];
+    use value::Value::{Int, Float};
+    struct TestCase(&'static str, Vec<Token>);
 
     #[test]
     fn positive() {
-        for TestCase(input, output) in TESTS_POSITIVE {
-            let tokens = tokenize(input);
-            assert_eq!(tokens, *output);
+        let tests: Vec<TestCase> = vec![
+            TestCase("2+2",
+                     vec![Number(Int(2)), Op(Plus), Number(Int(2))]),
+            TestCase("2++2",
+                     vec![Number(Int(2)), Op(Plus), Op(Plus), Number(Int(2))]),
+            TestCase("",
+                     vec![]),
+            TestCase("((2+555)+100)0",
+                     vec![Op(LeftParenthesis), Op(LeftParenthesis), Number(Int(2)), Op(Plus), Number(Int(555)),
+                         Op(RightParenthesis), Op(Plus), Number(Int(100)), Op(RightParenthesis), Number(Int(0))]),
+            TestCase("2 * 10",
+                     vec![Number(Int(2)), Whitespace(' '), Op(Asterisk), Whitespace(' '), Number(Int(10))]),
+            TestCase("Ans*10",
+                     vec![Ident("Ans".to_string()), Op(Asterisk), Number(Int(10))]),
+            TestCase("let x = 5; x",
+                     vec![Let, Whitespace(' '), Ident("x".to_string()), Whitespace(' '), Op(Equal),
+                         Whitespace(' '), Number(Int(5)), Op(Semicolon), Whitespace(' '), Ident("x".to_string())]),
+            TestCase("3.5+0.25",
+                     vec![Number(Float(3.5)), Op(Plus), Number(Float(0.25))]),
+            TestCase("6&3|1^^2",
+                     vec![Number(Int(6)), Op(Amper), Number(Int(3)), Op(Pipe), Number(Int(1)), Op(CaretCaret), Number(Int(2))]),
+            TestCase("1<<4>>2<3",
+                     vec![Number(Int(1)), Op(LeftShift), Number(Int(4)), Op(RightShift), Number(Int(2)),
+                         Op(LessThan), Number(Int(3))]),
+        ];
+        for TestCase(input, output) in &tests {
+            let tokens = tokenize(input).unwrap();
+            assert_eq!(&tokens, output);
             println!("{} converted to {:?} successfully", input, tokens);
         }
     }
 
     #[test]
-    #[should_panic]
     fn negative_1() {
-        tokenize("0001");
+        assert_eq!(tokenize("0001"), Err(LexError::LeadingZero { pos: 1 }));
     }
 
     #[test]
-    #[should_panic]
     fn negative_2() {
-        tokenize("abc");
+        assert_eq!(tokenize("1@2"), Err(LexError::IllegalChar { pos: 1 }));
+    }
+
+    #[test]
+    fn negative_3() {
+        assert_eq!(tokenize("1."), Err(LexError::MalformedFloat { pos: 1 }));
     }
-}
\ No newline at end of file
+}
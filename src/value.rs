@@ -0,0 +1,40 @@
+use std::fmt;
+use std::ops::Neg;
+
+/// A runtime value: either an exact integer or a floating-point number.
+/// Operators promote an `Int` to a `Float` whenever the two operands differ in kind.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Value {
+    Int(i128),
+    Float(f64),
+}
+
+impl Value {
+    /// Widens the value to `f64`, losslessly for the common calculator-sized integers
+    pub fn as_f64(&self) -> f64 {
+        match *self {
+            Value::Int(v) => v as f64,
+            Value::Float(v) => v,
+        }
+    }
+}
+
+impl Neg for Value {
+    type Output = Value;
+
+    fn neg(self) -> Value {
+        match self {
+            Value::Int(v) => Value::Int(-v),
+            Value::Float(v) => Value::Float(-v),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Int(v) => write!(f, "{}", v),
+            Value::Float(v) => write!(f, "{}", v),
+        }
+    }
+}